@@ -6,7 +6,6 @@ use windows::{
     Win32::System::LibraryLoader::GetModuleHandleA,
     Win32::UI::Input::KeyboardAndMouse::*,
 };
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
 use tray_icon::{TrayIconBuilder, menu::{Menu, MenuEvent, MenuItem}, Icon};
@@ -16,8 +15,9 @@ use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Mutex;
+use std::time::Instant;
 use once_cell::sync::Lazy;
 
 const WM_KEYDOWN: u32 = 0x0100;
@@ -25,30 +25,182 @@ const WM_KEYUP: u32 = 0x0101;
 const WM_SYSKEYDOWN: u32 = 0x0104;
 const WM_SYSKEYUP: u32 = 0x0105;
 
-static SHOULD_SEND_IME_OFF: AtomicBool = AtomicBool::new(false);
-static SHOULD_SEND_IME_ON: AtomicBool = AtomicBool::new(false);
-
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
     log_level: String,
     keys: KeyConfig,
 }
 
+/// 仮想キーコード。config.json上では"LControl"のような読みやすいキー名、または
+/// 後方互換のための素のVKコード整数のどちらでも受け付ける。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct VkCode(u32);
+
+/// config.jsonで使う読みやすいキー名の一覧。`get_key_name`(vk→name)と
+/// `VkCode`のパース(name→vk)の両方がこの一つの表を参照するので、常に
+/// 互いの逆変換になっている。
+fn named_keys() -> Vec<(String, u32)> {
+    let mut keys = vec![
+        ("Backspace".to_string(), 0x08),
+        ("Tab".to_string(), 0x09),
+        ("Enter".to_string(), 0x0D),
+        ("Shift".to_string(), 0x10),
+        ("Ctrl".to_string(), 0x11),
+        ("Alt".to_string(), 0x12),
+        ("Pause".to_string(), 0x13),
+        ("CapsLock".to_string(), 0x14),
+        ("Henkan".to_string(), 0x1C),
+        ("Muhenkan".to_string(), 0x1D),
+        ("IME_ON".to_string(), 0x16),
+        ("IME_OFF".to_string(), 0x1A),
+        ("Esc".to_string(), 0x1B),
+        ("Space".to_string(), 0x20),
+        ("Left".to_string(), 0x25),
+        ("Up".to_string(), 0x26),
+        ("Right".to_string(), 0x27),
+        ("Down".to_string(), 0x28),
+        ("Delete".to_string(), 0x2E),
+        ("LShift".to_string(), 0xA0),
+        ("RShift".to_string(), 0xA1),
+        ("LControl".to_string(), 0xA2),
+        ("RControl".to_string(), 0xA3),
+        ("LAlt".to_string(), 0xA4),
+        ("RAlt".to_string(), 0xA5),
+    ];
+    for digit in b'0'..=b'9' {
+        keys.push(((digit as char).to_string(), digit as u32));
+    }
+    for letter in b'A'..=b'Z' {
+        keys.push(((letter as char).to_string(), letter as u32));
+    }
+    for n in 1..=24u32 {
+        keys.push((format!("F{}", n), 0x6F + n));
+    }
+    keys
+}
+
+static NAME_TO_VK: Lazy<HashMap<String, u32>> = Lazy::new(|| named_keys().into_iter().collect());
+static VK_TO_NAME: Lazy<HashMap<u32, String>> =
+    Lazy::new(|| named_keys().into_iter().map(|(name, vk)| (vk, name)).collect());
+
+impl Serialize for VkCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match VK_TO_NAME.get(&self.0) {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_u32(self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for VkCode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct VkCodeVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for VkCodeVisitor {
+            type Value = VkCode;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a key name (e.g. \"LControl\") or a numeric virtual-key code")
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<VkCode, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(VkCode(v as u32))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<VkCode, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(VkCode(v as u32))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<VkCode, E>
+            where
+                E: serde::de::Error,
+            {
+                if let Some(&vk) = NAME_TO_VK.get(v) {
+                    Ok(VkCode(vk))
+                } else if let Ok(n) = v.parse::<u32>() {
+                    Ok(VkCode(n))
+                } else {
+                    warn!("不明なキー名です。設定を確認してください: \"{}\"", v);
+                    Err(serde::de::Error::custom(format!("unknown key name: \"{}\"", v)))
+                }
+            }
+        }
+
+        deserializer.deserialize_any(VkCodeVisitor)
+    }
+}
+
+/// トリガーキーをタップしたときに発火させる動作。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Action {
+    /// 単一の仮想キーをタップ(down+up)する。
+    Key { vk: VkCode },
+    /// 仮想キーを並び順にタップしていく。
+    Sequence { vks: Vec<VkCode> },
+    /// 文字列をそのまま入力として送出する。
+    Text { text: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyEntry {
+    trigger: VkCode,
+    action: Action,
+    /// trueの場合、このトリガーキーの押下/解放イベントを`CallNextHookEx`に渡さず、
+    /// 元のキーとしての動作(CapsLockのトグルなど)を完全に抑制する。
+    #[serde(default)]
+    swallow: bool,
+    /// trueの場合、`trigger`のvkCodeではなくscanCodeと拡張キーフラグの組み合わせで
+    /// マッチさせる。Ctrl/Alt/Shiftは左右でvkCodeが曖昧になることがあるため、
+    /// 物理的にどちらのキーかを確実に区別したい場合に使う。左右のCtrl/Alt/Shift
+    /// 以外のキーでは警告を出したうえでvkCodeでマッチする。
+    #[serde(default)]
+    physical: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct KeyConfig {
-    ime_off: u32,
-    ime_on: u32,
+    entries: Vec<KeyEntry>,
 }
 
 impl Default for KeyConfig {
     fn default() -> Self {
         KeyConfig {
-            ime_off: VK_LCONTROL.0 as u32,
-            ime_on: VK_RCONTROL.0 as u32,
+            entries: vec![
+                KeyEntry { trigger: VkCode(VK_LCONTROL.0 as u32), action: Action::Key { vk: VkCode(VK_IME_OFF.0 as u32) }, swallow: false, physical: false },
+                KeyEntry { trigger: VkCode(VK_RCONTROL.0 as u32), action: Action::Key { vk: VkCode(VK_IME_ON.0 as u32) }, swallow: false, physical: false },
+            ],
         }
     }
 }
 
+/// 左右のCtrl/Alt/ShiftについてvkCode(0xA0〜0xA5)からscanCodeと拡張キーフラグの
+/// 組(物理的な識別子)を求める。どちらのキーかをvkCodeでは曖昧になりがちな
+/// キーのみ対応しており、それ以外は`None`を返す。
+fn physical_identity(vk: u32) -> Option<(u32, bool)> {
+    match vk {
+        0xA0 => Some((0x2A, false)), // LShift
+        0xA1 => Some((0x36, false)), // RShift
+        0xA2 => Some((0x1D, false)), // LControl
+        0xA3 => Some((0x1D, true)),  // RControl
+        0xA4 => Some((0x38, false)), // LAlt
+        0xA5 => Some((0x38, true)),  // RAlt
+        _ => None,
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -85,74 +237,129 @@ fn load_config() -> Config {
     }
 }
 
-fn get_key_name(vk_code: u32) -> &'static str {
-    match vk_code {
-        0x08 => "Backspace",
-        0x09 => "Tab",
-        0x0D => "Enter",
-        0x10 => "Shift",
-        0x11 => "Ctrl",
-        0x12 => "Alt",
-        0x13 => "Pause",
-        0x14 => "CapsLock",
-        0x16 => "IME_ON",
-        0x1A => "IME_OFF",
-        0x1B => "Esc",
-        0x20 => "Space",
-        0x25 => "←",
-        0x26 => "↑",
-        0x27 => "→",
-        0x28 => "↓",
-        0x2E => "Delete",
-        0x30..=0x39 => "0-9",
-        0x41..=0x5A => "A-Z",
-        0xA0 => "左Shift",
-        0xA1 => "右Shift",
-        0xA2 => "左Ctrl",
-        0xA3 => "右Ctrl",
-        0xA4 => "左Alt",
-        0xA5 => "右Alt",
-        _ => "その他",
+fn get_key_name(vk_code: u32) -> String {
+    match VK_TO_NAME.get(&vk_code) {
+        Some(name) => name.clone(),
+        None => format!("Unknown(0x{:02X})", vk_code),
     }
 }
 
-fn send_ime_off() {
-    unsafe {
-        let mut inputs: Vec<INPUT> = Vec::with_capacity(2);
+fn parse_log_level(log_level: &str) -> log::LevelFilter {
+    match log_level.to_lowercase().as_str() {
+        "error" => log::LevelFilter::Error,
+        "warn" => log::LevelFilter::Warn,
+        "info" => log::LevelFilter::Info,
+        "debug" => log::LevelFilter::Debug,
+        "trace" => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Warn,
+    }
+}
+
+/// `SendInput`で送出する全イベントに付与する目印。フックコールバックはこの値を見て
+/// 自分自身が注入したイベントを素通しし、`KEY_MANAGER`に再度食わせてフィードバック
+/// ループに陥ることを防ぐ。
+const SELF_INJECTED_SENTINEL: usize = 0x4B50_0001;
 
-        let mut input = INPUT::default();
-        input.r#type = INPUT_KEYBOARD;
-        input.Anonymous.ki.wVk = VK_IME_OFF;
-        inputs.push(input);
-        
-        let mut input = INPUT::default();
-        input.r#type = INPUT_KEYBOARD;
-        input.Anonymous.ki.wVk = VK_IME_OFF;
+fn push_key_event(inputs: &mut Vec<INPUT>, vk: VIRTUAL_KEY, key_up: bool) {
+    let mut input = INPUT::default();
+    input.r#type = INPUT_KEYBOARD;
+    input.Anonymous.ki.wVk = vk;
+    if key_up {
         input.Anonymous.ki.dwFlags = KEYEVENTF_KEYUP;
-        inputs.push(input);
+    }
+    input.Anonymous.ki.dwExtraInfo = SELF_INJECTED_SENTINEL;
+    inputs.push(input);
+}
 
-        let ret = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32);
-        info!("IME OFFキーを送信しました: {}", ret);
+fn push_unicode_event(inputs: &mut Vec<INPUT>, code_unit: u16) {
+    let mut down = INPUT::default();
+    down.r#type = INPUT_KEYBOARD;
+    down.Anonymous.ki.wScan = code_unit;
+    down.Anonymous.ki.dwFlags = KEYEVENTF_UNICODE;
+    down.Anonymous.ki.dwExtraInfo = SELF_INJECTED_SENTINEL;
+    inputs.push(down);
+
+    let mut up = INPUT::default();
+    up.r#type = INPUT_KEYBOARD;
+    up.Anonymous.ki.wScan = code_unit;
+    up.Anonymous.ki.dwFlags = KEYEVENTF_UNICODE | KEYEVENTF_KEYUP;
+    up.Anonymous.ki.dwExtraInfo = SELF_INJECTED_SENTINEL;
+    inputs.push(up);
+}
+
+/// VkKeyScanWを使って1文字をタップ(必要ならShift/Ctrl/Altを添えて)する。
+/// 現在のキーボードレイアウトで表現できない文字はUnicode注入にフォールバックする。
+fn push_ascii_char(inputs: &mut Vec<INPUT>, ch: u8) {
+    let scan = unsafe { VkKeyScanW(ch as u16) };
+    if scan == -1 {
+        push_unicode_event(inputs, ch as u16);
+        return;
+    }
+
+    let vk = VIRTUAL_KEY((scan & 0xFF) as u16);
+    let shift_state = (scan >> 8) as u8;
+    let modifiers = [
+        (shift_state & 0x01 != 0, VK_SHIFT),
+        (shift_state & 0x02 != 0, VK_CONTROL),
+        (shift_state & 0x04 != 0, VK_MENU),
+    ];
+
+    for &(pressed, vk_mod) in &modifiers {
+        if pressed {
+            push_key_event(inputs, vk_mod, false);
+        }
+    }
+    push_key_event(inputs, vk, false);
+    push_key_event(inputs, vk, true);
+    for &(pressed, vk_mod) in modifiers.iter().rev() {
+        if pressed {
+            push_key_event(inputs, vk_mod, true);
+        }
     }
 }
 
-fn send_ime_on() {
+fn send_vk_tap(vk: u32) {
     unsafe {
         let mut inputs: Vec<INPUT> = Vec::with_capacity(2);
+        let vk = VIRTUAL_KEY(vk as u16);
+        push_key_event(&mut inputs, vk, false);
+        push_key_event(&mut inputs, vk, true);
 
-        let mut input = INPUT::default();
-        input.r#type = INPUT_KEYBOARD;
-        input.Anonymous.ki.wVk = VK_IME_ON;
-        inputs.push(input);
-        
-        let mut input = INPUT::default();
-        input.r#type = INPUT_KEYBOARD;
-        input.Anonymous.ki.wVk = VK_IME_ON;
-        input.Anonymous.ki.dwFlags = KEYEVENTF_KEYUP;
-        inputs.push(input);
+        let ret = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32);
+        info!("キーを送信しました: {}", ret);
+    }
+}
+
+fn send_vk_sequence(vks: &[u32]) {
+    for &vk in vks {
+        send_vk_tap(vk);
+    }
+}
 
+fn send_text(text: &str) {
+    let mut inputs: Vec<INPUT> = Vec::new();
+    for ch in text.chars() {
+        if ch.is_ascii() {
+            push_ascii_char(&mut inputs, ch as u8);
+        } else {
+            let mut buf = [0u16; 2];
+            for unit in ch.encode_utf16(&mut buf) {
+                push_unicode_event(&mut inputs, *unit);
+            }
+        }
+    }
+
+    unsafe {
         let ret = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32);
-        info!("IME ONキーを送信しました: {}", ret);
+        info!("テキストを送信しました ({}文字): {}", text.chars().count(), ret);
+    }
+}
+
+fn send_action(action: &Action) {
+    match action {
+        Action::Key { vk } => send_vk_tap(vk.0),
+        Action::Sequence { vks } => send_vk_sequence(&vks.iter().map(|vk| vk.0).collect::<Vec<_>>()),
+        Action::Text { text } => send_text(text),
     }
 }
 
@@ -162,108 +369,227 @@ fn is_key_pressed(vk_code: VIRTUAL_KEY) -> bool {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum KeyAction {
-    ImeOff,
-    ImeOn,
-}
-
 struct KeyState {
     pressed: bool,
-    action: Option<KeyAction>,
+    pressed_at: Option<Instant>,
+    action: Action,
+    swallow: bool,
 }
 
+/// tap-hold方式のキー管理。トリガーキーの押下中にトリガー以外のキーが押されなかった
+/// 場合に対応する`action`を発火させ、トリガー以外のキーとの同時押し(ホールド)では
+/// 発火させない。トリガーキー同士の同時押しはこの判定の対象外で、それぞれの`action`
+/// が発火する。同時押しでも`swallow`が無効なトリガーは通常のキーとして素通しし、
+/// 有効なトリガーは常に消費する。
+///
+/// `by_vk`はvkCodeで、`by_physical`は(scanCode, 拡張キーフラグ)の組で引くトリガー
+/// を保持する。後者は`physical: true`と設定されたエントリ専用で、Ctrl/Alt/Shiftの
+/// 左右を確実に区別したい場合に使う。
 struct KeyManager {
-    states: HashMap<u32, KeyState>,
+    by_vk: HashMap<u32, KeyState>,
+    by_physical: HashMap<(u32, bool), KeyState>,
     other_key_pressed: bool,
 }
 
 impl KeyManager {
     fn new() -> Self {
         let config = load_config();
-        let mut states = HashMap::new();
-        states.insert(config.keys.ime_off, KeyState {
-            pressed: false,
-            action: Some(KeyAction::ImeOff),
-        });
-        states.insert(config.keys.ime_on, KeyState {
-            pressed: false,
-            action: Some(KeyAction::ImeOn),
-        });
-        
+        Self::from_key_config(config.keys)
+    }
+
+    fn from_key_config(key_config: KeyConfig) -> Self {
+        let (by_vk, by_physical) = Self::build_states(key_config.entries);
         KeyManager {
-            states,
+            by_vk,
+            by_physical,
             other_key_pressed: false,
         }
     }
 
-    fn key_down(&mut self, key_code: u32) {
-        if let Some(state) = self.states.get_mut(&key_code) {
+    fn build_states(entries: Vec<KeyEntry>) -> (HashMap<u32, KeyState>, HashMap<(u32, bool), KeyState>) {
+        let mut by_vk = HashMap::new();
+        let mut by_physical = HashMap::new();
+        for entry in entries {
+            let state = KeyState {
+                pressed: false,
+                pressed_at: None,
+                action: entry.action,
+                swallow: entry.swallow,
+            };
+            if entry.physical {
+                match physical_identity(entry.trigger.0) {
+                    Some(identity) => {
+                        by_physical.insert(identity, state);
+                        continue;
+                    }
+                    None => {
+                        warn!(
+                            "\"{}\"はphysicalでの左右判定に対応していないため、vkCodeでマッチします",
+                            get_key_name(entry.trigger.0)
+                        );
+                    }
+                }
+            }
+            by_vk.insert(entry.trigger.0, state);
+        }
+        (by_vk, by_physical)
+    }
+
+    /// config.jsonの変更をトリガーキーの設定に反映する。保持中の`pressed`/
+    /// `other_key_pressed`状態をリセットすることで、リロード前の押下状態が
+    /// 新しい状態に持ち越されないようにする。
+    fn reload(&mut self, key_config: KeyConfig) {
+        let (by_vk, by_physical) = Self::build_states(key_config.entries);
+        self.by_vk = by_vk;
+        self.by_physical = by_physical;
+        self.other_key_pressed = false;
+    }
+
+    fn state_mut(&mut self, key_code: u32, scan_code: u32, extended: bool) -> Option<&mut KeyState> {
+        if self.by_physical.contains_key(&(scan_code, extended)) {
+            self.by_physical.get_mut(&(scan_code, extended))
+        } else {
+            self.by_vk.get_mut(&key_code)
+        }
+    }
+
+    fn any_pressed(&self) -> bool {
+        self.by_vk.values().any(|state| state.pressed) || self.by_physical.values().any(|state| state.pressed)
+    }
+
+    fn key_down(&mut self, key_code: u32, scan_code: u32, extended: bool) {
+        let other_key_pressed_before = self.any_pressed();
+        if let Some(state) = self.state_mut(key_code, scan_code, extended) {
+            // キーリピートによる押下イベントでは押下開始時刻を更新しない
+            if !state.pressed {
+                state.pressed_at = Some(Instant::now());
+            }
             state.pressed = true;
-        } else if self.states.iter().any(|(_, state)| state.pressed) {
-            // 設定されたキー以外が押された場合
+        } else if other_key_pressed_before {
+            // 設定されたトリガーキーを押している間に他のキーが押された場合
             self.other_key_pressed = true;
         }
     }
 
-    fn key_up(&mut self, key_code: u32) -> Option<KeyAction> {
-        if let Some(state) = self.states.get_mut(&key_code) {
+    fn key_up(&mut self, key_code: u32, scan_code: u32, extended: bool) -> Option<Action> {
+        if let Some(state) = self.state_mut(key_code, scan_code, extended) {
             state.pressed = false;
-            if !self.other_key_pressed {
-                return state.action;
+            let held_alone = !self.other_key_pressed;
+            if let Some(pressed_at) = state.pressed_at.take() {
+                info!("キー保持時間: {:?} (単独押下: {})", pressed_at.elapsed(), held_alone);
             }
             self.other_key_pressed = false;
+            if held_alone {
+                return Some(state.action.clone());
+            }
         }
         None
     }
+
+    /// このキーをトリガーとして登録しており、かつ`swallow`が有効な場合に真を返す。
+    /// 真の場合、呼び出し側は元のキーイベントを`CallNextHookEx`に渡さずに消費する。
+    fn should_swallow(&self, key_code: u32, scan_code: u32, extended: bool) -> bool {
+        if let Some(state) = self.by_physical.get(&(scan_code, extended)) {
+            return state.swallow;
+        }
+        self.by_vk.get(&key_code).map_or(false, |state| state.swallow)
+    }
 }
 
 static KEY_MANAGER: Lazy<Mutex<KeyManager>> = Lazy::new(|| Mutex::new(KeyManager::new()));
 
+/// タップ発火したがまだ送出していない(トリガーキーの物理的な解放待ちの)アクション。
+/// `(トリガーのvkCode, アクション)`の組で保持する。
+static PENDING_ACTIONS: Lazy<Mutex<VecDeque<(u32, Action)>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
 unsafe extern "system" fn hook_callback(code: i32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
     if code >= 0 {
         let vk_code = l_param.0 as *const KBDLLHOOKSTRUCT;
         if !vk_code.is_null() {
+            // 自分自身が`SendInput`で注入したイベントは無視する。ここで素通しせずに
+            // `KEY_MANAGER`へ食わせると、送出したキーを押下として誤認識してしまう。
+            if (*vk_code).dwExtraInfo == SELF_INJECTED_SENTINEL {
+                return CallNextHookEx(None, code, w_param, l_param);
+            }
+
             let key_code = (*vk_code).vkCode;
+            let scan_code = (*vk_code).scanCode;
+            // LLKHF_EXTENDED (0x01): 右手側のCtrl/Altはこのビットが立ち、左手側では立たない。
+            let extended = (*vk_code).flags.0 & 0x01 != 0;
+            let swallow = KEY_MANAGER.lock().unwrap().should_swallow(key_code, scan_code, extended);
             match w_param.0 as u32 {
                 WM_KEYDOWN | WM_SYSKEYDOWN => {
                     info!("キー押下: {} ({})", get_key_name(key_code), key_code);
-                    KEY_MANAGER.lock().unwrap().key_down(key_code);
+                    KEY_MANAGER.lock().unwrap().key_down(key_code, scan_code, extended);
                 }
                 WM_KEYUP | WM_SYSKEYUP => {
                     info!("キー解放: {} ({})", get_key_name(key_code), key_code);
-                    if let Some(action) = KEY_MANAGER.lock().unwrap().key_up(key_code) {
-                        match action {
-                            KeyAction::ImeOff => SHOULD_SEND_IME_OFF.store(true, Ordering::SeqCst),
-                            KeyAction::ImeOn => SHOULD_SEND_IME_ON.store(true, Ordering::SeqCst),
-                        }
+                    if let Some(action) = KEY_MANAGER.lock().unwrap().key_up(key_code, scan_code, extended) {
+                        PENDING_ACTIONS.lock().unwrap().push_back((key_code, action));
                     }
                 }
                 _ => {}
             }
+
+            if swallow {
+                return LRESULT(1);
+            }
         }
     }
     CallNextHookEx(None, code, w_param, l_param)
 }
 
-fn ime_control_thread() {
+/// 保留中のアクションを、対応するトリガーキーが物理的に解放されるまで待ってから送出する。
+/// `GetAsyncKeyState`はフックよりわずかに反映が遅れることがあるため、この一拍置くことで
+/// 送出したキーをトリガーキー自身の押下中として誤検知するのを防ぐ。
+fn action_dispatch_thread() {
     loop {
-        if SHOULD_SEND_IME_OFF.load(Ordering::SeqCst) {
-            if !is_key_pressed(VK_LCONTROL) {
-                send_ime_off();
-                SHOULD_SEND_IME_OFF.store(false, Ordering::SeqCst);
-            }
-        }
-        if SHOULD_SEND_IME_ON.load(Ordering::SeqCst) {
-            if !is_key_pressed(VK_RCONTROL) {
-                send_ime_on();
-                SHOULD_SEND_IME_ON.store(false, Ordering::SeqCst);
-            }
+        let ready: Vec<Action> = {
+            let mut pending = PENDING_ACTIONS.lock().unwrap();
+            let mut ready = Vec::new();
+            pending.retain(|(trigger, action)| {
+                if is_key_pressed(VIRTUAL_KEY(*trigger as u16)) {
+                    true
+                } else {
+                    ready.push(action.clone());
+                    false
+                }
+            });
+            ready
+        };
+        for action in ready {
+            send_action(&action);
         }
         thread::sleep(Duration::from_millis(10));
     }
 }
 
+/// config.jsonの更新日時を1秒おきにポーリングし、変更を検知したら設定を再読み込みして
+/// `KEY_MANAGER`とログレベルに反映する。uinput系のリマッパーがデバイスを掴み直さずに
+/// キーマップを差し替えるのと同様に、アプリを再起動せずにキー設定を更新できる。
+fn config_watch_thread() {
+    let config_path = "config.json";
+    let mut last_modified = fs::metadata(config_path).and_then(|m| m.modified()).ok();
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+
+        let modified = match fs::metadata(config_path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => continue,
+        };
+        if last_modified == Some(modified) {
+            continue;
+        }
+        last_modified = Some(modified);
+
+        info!("設定ファイルの変更を検知しました。再読み込みします。");
+        let config = load_config();
+        log::set_max_level(parse_log_level(&config.log_level));
+        KEY_MANAGER.lock().unwrap().reload(config.keys);
+    }
+}
+
 fn create_icon() -> Icon {
     // 16x16の白い「あ」の画像を作成
     let mut img = ImageBuffer::new(16, 16);
@@ -278,21 +604,15 @@ fn create_icon() -> Icon {
 fn main() -> windows::core::Result<()> {
     // 設定の読み込み
     let config = load_config();
-    let log_level = match config.log_level.to_lowercase().as_str() {
-        "error" => log::LevelFilter::Error,
-        "warn" => log::LevelFilter::Warn,
-        "info" => log::LevelFilter::Info,
-        "debug" => log::LevelFilter::Debug,
-        "trace" => log::LevelFilter::Trace,
-        _ => log::LevelFilter::Warn,
-    };
-    
+    let log_level = parse_log_level(&config.log_level);
+
     // ログ設定
     simple_logging::log_to_file("kana_power.log", log_level).unwrap();
     
     info!("キー入力の監視を開始します。");
-    info!("IME OFFキー: {}", get_key_name(config.keys.ime_off));
-    info!("IME ONキー: {}", get_key_name(config.keys.ime_on));
+    for entry in &config.keys.entries {
+        info!("トリガーキー: {} -> {:?}", get_key_name(entry.trigger.0), entry.action);
+    }
     
     // トレイアイコンの設定
     let (tx, rx) = mpsc::channel();
@@ -313,9 +633,14 @@ fn main() -> windows::core::Result<()> {
         event_tx.send(()).unwrap();
     })));
 
-    // IME制御用スレッドを起動
+    // アクション送出スレッドを起動
+    thread::spawn(|| {
+        action_dispatch_thread();
+    });
+
+    // 設定ファイルのホットリロード監視スレッドを起動
     thread::spawn(|| {
-        ime_control_thread();
+        config_watch_thread();
     });
 
     unsafe {